@@ -4,7 +4,7 @@ use std::cmp::Ordering;
 use std::collections::HashSet;
 
 pub type AuthorityId = usize;
-pub type BLSSignature = u64;
+pub type Stake = u64;
 
 const COMMIT_THRESHOLD: i64 = 3;
 
@@ -13,6 +13,55 @@ pub enum NSResult {
     Error(String),
 }
 
+/// Public half of an authority's key pair, used to verify signatures and aggregates. The scheme
+/// is a linear placeholder, not BLS: a `PublicKey` holds the same value as its `SecretKey`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicKey(u64);
+
+/// Secret half of an authority's key pair. `Nightshade::new` only ever receives the owner's
+/// own secret key; every other authority is known solely by its `PublicKey`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecretKey(u64);
+
+impl SecretKey {
+    pub fn from_seed(seed: u64) -> Self {
+        SecretKey(seed)
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.0)
+    }
+
+    fn sign(&self, digest: u64) -> BLSSignature {
+        BLSSignature(self.0.wrapping_mul(digest))
+    }
+}
+
+/// An aggregate signature: individual signatures over the same digest can be folded into
+/// one another with `aggregate`, and the result verifies against the aggregate of the matching
+/// public keys. A signature is the secret key times the digest and aggregation is addition
+/// (both wrapping); no pairing-based BLS cryptography is involved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BLSSignature(u64);
+
+impl BLSSignature {
+    fn empty() -> Self {
+        BLSSignature(0)
+    }
+
+    fn aggregate(&mut self, other: BLSSignature) {
+        self.0 = self.0.wrapping_add(other.0);
+    }
+
+    fn verify(&self, digest: u64, public_keys: &[PublicKey]) -> bool {
+        let expected = public_keys
+            .iter()
+            .fold(0u64, |acc, public_key| acc.wrapping_add(public_key.0.wrapping_mul(digest)));
+
+        expected == self.0
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct BareState {
     endorses: AuthorityId,
@@ -72,31 +121,70 @@ impl BareState {
             confidence1: -1,
         }
     }
+
+    /// Deterministic digest of the fields a signature is taken over.
+    fn digest(&self) -> u64 {
+        let mut digest = self.endorses as u64;
+        digest = digest.wrapping_mul(1_000_003).wrapping_add(self.confidence0 as u64);
+        digest = digest.wrapping_mul(1_000_003).wrapping_add(self.confidence1 as u64);
+        digest
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct SignedState {
     signature: BLSSignature,
+    // TODO: Use bitmask
+    signers: Vec<bool>,
     parent: Vec<BareState>,
 }
 
 impl SignedState {
-    fn new() -> Self {
+    fn new(num_authorities: usize) -> Self {
         Self {
-            signature: 0,
+            signature: BLSSignature::empty(),
+            signers: vec![false; num_authorities],
             parent: vec![],
         }
     }
 
-    fn update(&mut self, state: &State) {
-        // TODO: Update self.signature using state.get_signature
+    /// Folds `authority_id`'s contribution to `state` into this aggregate. Returns `false`
+    /// without modifying `self` if `authority_id` already contributed, so an aggregate can
+    /// never double-count an authority's stake or signature.
+    fn update(&mut self, authority_id: AuthorityId, state: &State) -> bool {
+        if self.signers[authority_id] {
+            return false;
+        }
+
+        self.signers[authority_id] = true;
         self.parent.push(state.bare_state.clone());
+        self.signature.aggregate(state.get_signature());
+        true
+    }
+
+    fn signer_ids<'a>(&'a self) -> impl Iterator<Item = AuthorityId> + 'a {
+        self.signers.iter().enumerate().filter(|(_, signed)| **signed).map(|(id, _)| id)
+    }
+
+    /// Verifies that `self` is a genuine aggregate signature over `digest`, contributed by
+    /// exactly the authorities marked in `signers`, and that those authorities together hold
+    /// more than 2/3 of `total_stake`.
+    fn verify(&self, digest: u64, public_keys: &[PublicKey], stakes: &[Stake], total_stake: Stake) -> bool {
+        let signer_stake: Stake = self.signer_ids().map(|id| stakes[id]).sum();
+
+        if 3 * signer_stake <= 2 * total_stake {
+            return false;
+        }
+
+        let signer_public_keys: Vec<PublicKey> = self.signer_ids().map(|id| public_keys[id]).collect();
+        self.signature.verify(digest, &signer_public_keys)
     }
 }
 
 #[derive(Debug, Clone, Eq)]
 pub struct State {
     bare_state: BareState,
+    signature: BLSSignature,
 
     // TODO: Proof might be empty at the beginning of consensus. Use enum instead?
     proof0: Option<SignedState>,
@@ -104,9 +192,13 @@ pub struct State {
 }
 
 impl State {
-    fn new(endorses: AuthorityId) -> Self {
+    fn new(endorses: AuthorityId, secret_key: &SecretKey) -> Self {
+        let bare_state = BareState::new(endorses);
+        let signature = secret_key.sign(bare_state.digest());
+
         Self {
-            bare_state: BareState::new(endorses),
+            bare_state,
+            signature,
             proof0: None,
             proof1: None,
         }
@@ -115,34 +207,102 @@ impl State {
     fn empty() -> Self {
         Self {
             bare_state: BareState::empty(),
+            signature: BLSSignature::empty(),
             proof0: None,
             proof1: None,
         }
     }
 
     /// Create new State with increased confidence using some proof
-    fn increase_confidence(&self, proof: SignedState) -> Self {
+    fn increase_confidence(&self, proof: SignedState, secret_key: &SecretKey) -> Self {
+        let bare_state = BareState {
+            endorses: self.bare_state.endorses,
+            confidence0: self.bare_state.confidence0 + 1,
+            confidence1: self.bare_state.confidence1,
+        };
+        let signature = secret_key.sign(bare_state.digest());
+
         Self {
-            bare_state: BareState {
-                endorses: self.bare_state.endorses,
-                confidence0: self.bare_state.confidence0 + 1,
-                confidence1: self.bare_state.confidence1,
-            },
+            bare_state,
+            signature,
             proof0: Some(proof),
             proof1: self.proof1.clone(),
         }
     }
 
+    /// Re-signs this `State`'s `bare_state` with `secret_key`, keeping everything else as-is.
+    /// `merge` adopts a `BareState` by cloning whichever argument's bare state wins, signature
+    /// and all, so an authority adopting someone else's endorsement otherwise ends up holding
+    /// that authority's signature rather than its own; proofs built from such a state would
+    /// aggregate a single authority's signature multiple times instead of one signature per
+    /// actual signer.
+    fn resign(&self, secret_key: &SecretKey) -> Self {
+        Self {
+            bare_state: self.bare_state.clone(),
+            signature: secret_key.sign(self.bare_state.digest()),
+            proof0: self.proof0.clone(),
+            proof1: self.proof1.clone(),
+        }
+    }
+
     fn can_commit(&self) -> bool {
         self.bare_state.confidence0 >= self.bare_state.confidence1 + COMMIT_THRESHOLD
     }
 
-    fn verify(&self) -> bool {
+    /// Verifies `proof0`/`proof1` against the `BareState`s they were actually collected over,
+    /// each requiring a 2/3-of-stake signing quorum. `proof0`, when present, must attest to
+    /// the state this `State` was built from (same `endorses`, `confidence0 - 1`), so a
+    /// valid-but-unrelated 2/3 proof can't be grafted onto a different `bare_state`; its
+    /// `confidence1` is not compared, since `merge` raises `confidence1` and keeps `proof0`. `proof1`,
+    /// when present, must cover a strictly lower-ranked (the second-highest endorsed) outcome
+    /// than `proof0`/`bare_state`. Every entry in a proof's `parent` must agree on the attested
+    /// `BareState`, not just the first one.
+    fn verify(&self, public_keys: &[PublicKey], stakes: &[Stake], total_stake: Stake) -> bool {
+        if let Some(proof0) = &self.proof0 {
+            match proof0.parent.first() {
+                Some(attested) => {
+                    if proof0.parent.iter().any(|parent| parent != attested) {
+                        return false;
+                    }
+
+                    if attested.endorses != self.bare_state.endorses
+                        || attested.confidence0 != self.bare_state.confidence0 - 1
+                    {
+                        return false;
+                    }
+
+                    if !proof0.verify(attested.digest(), public_keys, stakes, total_stake) {
+                        return false;
+                    }
+                }
+                None => return false,
+            }
+        }
+
+        if let Some(proof1) = &self.proof1 {
+            match proof1.parent.first() {
+                Some(attested) => {
+                    if proof1.parent.iter().any(|parent| parent != attested) {
+                        return false;
+                    }
+
+                    if !proof1.verify(attested.digest(), public_keys, stakes, total_stake) {
+                        return false;
+                    }
+
+                    if attested >= &self.bare_state {
+                        return false;
+                    }
+                }
+                None => return false,
+            }
+        }
+
         true
     }
 
     fn get_signature(&self) -> BLSSignature {
-        0
+        self.signature
     }
 
     fn endorses(&self) -> usize {
@@ -201,29 +361,49 @@ pub struct Nightshade {
     states: Vec<State>,
     // TODO: Use bitmask
     is_adversary: Vec<bool>,
-    best_state_counter: usize,
+    stakes: Vec<Stake>,
+    total_stake: Stake,
+    best_state_stake: Stake,
+    secret_key: SecretKey,
+    public_keys: Vec<PublicKey>,
     seen_bare_states: HashSet<BareState>,
     committed: Option<AuthorityId>,
 }
 
 impl Nightshade {
-    fn new(owner_id: AuthorityId, num_authorities: usize) -> Self {
+    fn new(
+        owner_id: AuthorityId,
+        num_authorities: usize,
+        stakes: Vec<Stake>,
+        secret_key: SecretKey,
+        public_keys: Vec<PublicKey>,
+    ) -> Self {
+        assert_eq!(stakes.len(), num_authorities);
+        assert_eq!(public_keys.len(), num_authorities);
+
         let mut states = vec![];
 
         for i in 0..num_authorities {
             if i == owner_id {
-                states.push(State::new(i));
+                states.push(State::new(i, &secret_key));
             } else {
                 states.push(State::empty());
             }
         }
 
+        let total_stake = stakes.iter().sum();
+        let best_state_stake = stakes[owner_id];
+
         Self {
             owner_id,
             num_authorities,
             states,
             is_adversary: vec![false; num_authorities],
-            best_state_counter: 1,
+            stakes,
+            total_stake,
+            best_state_stake,
+            secret_key,
+            public_keys,
             seen_bare_states: HashSet::new(),
             committed: None,
         }
@@ -242,7 +422,7 @@ impl Nightshade {
 
         // Verify this BareState only if it has not been successfully verified previously
         if !self.seen_bare_states.contains(&state.bare_state) {
-            if state.verify() {
+            if state.verify(&self.public_keys, &self.stakes, self.total_stake) {
                 self.seen_bare_states.insert(state.bare_state.clone());
             } else {
                 return NSResult::Error("Not valid state".to_string());
@@ -257,12 +437,14 @@ impl Nightshade {
             let new_state = merge(&self.states[self.owner_id], &state);
 
             if new_state != self.states[self.owner_id] {
-                self.states[self.owner_id] = new_state;
-                self.best_state_counter = 1;
+                // new_state may carry another authority's signature verbatim (see `resign`'s
+                // doc comment); re-sign with our own key so it's genuinely our endorsement.
+                self.states[self.owner_id] = new_state.resign(&self.secret_key);
+                self.best_state_stake = self.stakes[self.owner_id];
             }
 
             if state == self.states[self.owner_id] {
-                self.best_state_counter += 1;
+                self.best_state_stake += self.stakes[authority_id];
             }
 
             // We MIGHT NEED to increase confidence AT MOST ONCE after have committed for first time.
@@ -270,20 +452,31 @@ impl Nightshade {
             // nobody's second higher confidence can be C - 1 ever. The current implementation
             // doesn't bound confidence.
             if self.can_increase_confidence() {
-                let mut proof = SignedState::new();
+                let mut proof = SignedState::new(self.num_authorities);
+
+                // Collect proofs to create new state, stopping as soon as the signers gathered
+                // so far already cross the 2/3-of-stake quorum line.
+                let mut collected_stake: Stake = 0;
 
-                // Collect proofs to create new state
                 for i in 0..self.num_authorities {
                     if &self.states[i] == &self.states[self.owner_id] {
-                        proof.update(&self.states[i]);
+                        proof.update(i, &self.states[i]);
+                        collected_stake += self.stakes[i];
+
+                        if 3 * collected_stake > 2 * self.total_stake {
+                            break;
+                        }
                     }
                 }
 
-                let new_state = self.states[self.owner_id].increase_confidence(proof);
+                let new_state = self.states[self.owner_id].increase_confidence(proof, &self.secret_key);
 
-                self.states[self.owner_id] = new_state;
+                // Only adopt the freshly built state if its proof actually verifies.
+                if new_state.verify(&self.public_keys, &self.stakes, self.total_stake) {
+                    self.states[self.owner_id] = new_state;
 
-                self.best_state_counter = 1;
+                    self.best_state_stake = self.stakes[self.owner_id];
+                }
             }
 
             if self.states[self.owner_id].can_commit() {
@@ -305,11 +498,11 @@ impl Nightshade {
     }
 
     fn can_increase_confidence(&self) -> bool {
-        // Confidence is increased whenever we see that more than 2/3 of participants endorsed
-        // our current state.
+        // Confidence is increased whenever we see that authorities holding more than 2/3 of the
+        // total stake endorsed our current state.
         // We can use some fancy mechanism to not increase confidence every time we can, to avoid
         // being manipulated by malicious actors into a metastable equilibrium
-        self.best_state_counter > self.num_authorities * 2 / 3
+        3 * self.best_state_stake > 2 * self.total_stake
     }
 
     fn is_final(&self) -> bool {
@@ -327,12 +520,20 @@ mod tests {
 
     // TODO: Test proofs are collected properly
 
+    fn test_key_pairs(num_authorities: usize) -> (Vec<SecretKey>, Vec<PublicKey>) {
+        let secret_keys: Vec<_> = (0..num_authorities).map(|i| SecretKey::from_seed(i as u64 + 1)).collect();
+        let public_keys = secret_keys.iter().map(SecretKey::public_key).collect();
+        (secret_keys, public_keys)
+    }
+
     // TODO: Test consensus is reached on a sync scenario
     fn nightshade_all_sync(num_authorities: usize, num_rounds: usize) {
+        let stakes = vec![1; num_authorities];
+        let (secret_keys, public_keys) = test_key_pairs(num_authorities);
         let mut ns = vec![];
 
         for i in 0..num_authorities {
-            ns.push(Nightshade::new(i, num_authorities));
+            ns.push(Nightshade::new(i, num_authorities, stakes.clone(), secret_keys[i], public_keys.clone()));
         }
 
         for _ in 0..num_rounds {
@@ -371,8 +572,9 @@ mod tests {
 
     #[test]
     fn test_nightshade_basics() {
-        let mut ns0 = Nightshade::new(0, 2);
-        let mut ns1 = Nightshade::new(1, 2);
+        let (secret_keys, public_keys) = test_key_pairs(2);
+        let mut ns0 = Nightshade::new(0, 2, vec![1, 1], secret_keys[0], public_keys.clone());
+        let mut ns1 = Nightshade::new(1, 2, vec![1, 1], secret_keys[1], public_keys.clone());
         let state1 = ns1.state();
         assert_eq!(state1.endorses(), 1);
         let state0 = ns0.state();
@@ -384,11 +586,13 @@ mod tests {
     #[test]
     fn test_nightshade_basics_confidence() {
         let num_authorities = 4;
+        let stakes = vec![1; num_authorities];
+        let (secret_keys, public_keys) = test_key_pairs(num_authorities);
 
         let mut ns = vec![];
 
         for i in 0..num_authorities {
-            ns.push(Nightshade::new(i, num_authorities));
+            ns.push(Nightshade::new(i, num_authorities, stakes.clone(), secret_keys[i], public_keys.clone()));
         }
 
         for i in 2..4 {
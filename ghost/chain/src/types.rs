@@ -1,7 +1,13 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::Mutex;
+
 use chrono::prelude::{DateTime, NaiveDateTime, Utc};
-use primitives::crypto::signature::Signature;
+use chrono::Duration;
+use futures::sync::mpsc;
+use primitives::crypto::signature::{verify, PublicKey, Signature};
 use primitives::hash::CryptoHash;
-use primitives::types::BlockIndex;
+use primitives::types::{AuthorityId, BlockIndex};
 
 pub struct BlockHeader {
     /// Height of this block since the genesis block (height 0).
@@ -12,8 +18,8 @@ pub struct BlockHeader {
     pub prev_state_root: CryptoHash,
     /// Timestamp at which the block was built.
     pub timestamp: DateTime<Utc>,
-    /// Authority signatures.
-    pub signatures: Vec<Signature>,
+    /// Authority signatures, each paired with the id of the authority that produced it.
+    pub signatures: Vec<(AuthorityId, Signature)>,
     /// Total weight.
     pub total_weight: Weight,
 }
@@ -22,6 +28,12 @@ impl BlockHeader {
     pub fn hash(&self) -> CryptoHash {
         CryptoHash::default()
     }
+
+    /// Weight directly approving this block (one unique authority per entry in `signatures`),
+    /// as opposed to `total_weight`, which accumulates up the whole fork.
+    pub fn approval_weight(&self) -> Weight {
+        Weight::from_num(self.signatures.len() as u64)
+    }
 }
 
 pub struct Bytes(Vec<u8>);
@@ -37,6 +49,7 @@ impl Block {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlockStatus {
     /// Block is the "next" block, updating the chain head.
     Next,
@@ -51,6 +64,109 @@ pub enum BlockStatus {
 /// Handles downstream processing of valid blocks by the rest tof the system.
 pub trait ChainAdapter {
     fn block_accepted(&self, block: &Block, status: BlockStatus);
+
+    /// Called once a `Justification` has been independently verified and imported for the block
+    /// at `height`/`block_hash`. Default is a no-op so existing adapters don't need to change.
+    fn justification_imported(&self, block_hash: CryptoHash, height: BlockIndex) {
+        let _ = (block_hash, height);
+    }
+}
+
+/// Configuration for the optional "late head" re-org rule: a proposer building the next block
+/// may skip a freshly-arrived canonical head and build on its parent instead, if the head looks
+/// like it was released too late to have been fairly voted on.
+#[derive(Debug, Clone, Copy)]
+pub struct ForkChoiceConfig {
+    /// Disables the rule entirely; the canonical head is always built upon.
+    pub disable_reorgs: bool,
+    /// A head is only eligible to be skipped when its approval `Weight` is below this
+    /// percentage of the expected committee weight.
+    pub reorg_weight_threshold_percent: u8,
+    /// The rule is only attempted while the chain has finalized within this many heights of the
+    /// current head, so a stalled finalizer doesn't make re-orgs more aggressive.
+    pub max_depth_since_finalization: BlockIndex,
+}
+
+impl Default for ForkChoiceConfig {
+    fn default() -> Self {
+        Self {
+            disable_reorgs: false,
+            reorg_weight_threshold_percent: 20,
+            max_depth_since_finalization: 2,
+        }
+    }
+}
+
+/// Decides whether a proposer building at `new_height` should skip `head` (a recent, possibly
+/// late, block) and build on `head_parent` instead. This only returns the decision;
+/// `apply_late_head_reorg` reports it as `BlockStatus::Reorg` through `ChainAdapter::block_accepted`.
+///
+/// A re-org is only taken when all of the following hold:
+/// - `new_height` is exactly one slot past `head`, and `head` is itself exactly one slot past
+///   `head_parent` (a re-org only ever un-does a single, most-recent block);
+/// - the chain has finalized within `config.max_depth_since_finalization` heights of `head`;
+/// - `head`'s own approval `Weight` (not the cumulative fork weight) is below
+///   `config.reorg_weight_threshold_percent` of `expected_committee_weight`;
+/// - `head` arrived after its attestation deadline, `head_slot_start + attestation_deadline`.
+pub fn should_reorg_late_head(
+    config: &ForkChoiceConfig,
+    head: &BlockHeader,
+    head_parent: &BlockHeader,
+    new_height: BlockIndex,
+    expected_committee_weight: Weight,
+    head_slot_start: DateTime<Utc>,
+    attestation_deadline: Duration,
+    finalized_height: BlockIndex,
+) -> bool {
+    if config.disable_reorgs {
+        return false;
+    }
+
+    if new_height != head.height + 1 || head.height != head_parent.height + 1 {
+        return false;
+    }
+
+    if head.height.saturating_sub(finalized_height) > config.max_depth_since_finalization {
+        return false;
+    }
+
+    if !head.approval_weight().is_below_percent_of(config.reorg_weight_threshold_percent, expected_committee_weight) {
+        return false;
+    }
+
+    head.timestamp > head_slot_start + attestation_deadline
+}
+
+/// Evaluates `should_reorg_late_head` for a proposer building at `new_height` and, if the rule
+/// fires, reports `head` being skipped as `BlockStatus::Reorg` through `adapter.block_accepted`.
+/// Returns whether a re-org was reported.
+pub fn apply_late_head_reorg<A: ChainAdapter>(
+    adapter: &A,
+    config: &ForkChoiceConfig,
+    head: &Block,
+    head_parent: &BlockHeader,
+    new_height: BlockIndex,
+    expected_committee_weight: Weight,
+    head_slot_start: DateTime<Utc>,
+    attestation_deadline: Duration,
+    finalized_height: BlockIndex,
+) -> bool {
+    let reorg = should_reorg_late_head(
+        config,
+        &head.header,
+        head_parent,
+        new_height,
+        expected_committee_weight,
+        head_slot_start,
+        attestation_deadline,
+        finalized_height,
+    );
+
+    if reorg {
+        adapter.block_accepted(head, BlockStatus::Reorg);
+    }
+
+    reorg
 }
 
 pub struct NoopAdapter {}
@@ -69,6 +185,21 @@ pub struct Weight {
     num: u64,
 }
 
+impl Weight {
+    pub fn to_num(&self) -> u64 {
+        self.num
+    }
+
+    pub fn from_num(num: u64) -> Self {
+        Self { num }
+    }
+
+    /// Whether this weight is below `percent`% of `total`.
+    fn is_below_percent_of(&self, percent: u8, total: Weight) -> bool {
+        self.num * 100 < total.num * percent as u64
+    }
+}
+
 /// The tip of a fork. A handle to the fork ancestry from its leaf in the
 /// blockchain tree. References the max height and the latest and previous
 /// blocks for convenience and the total weight.
@@ -99,4 +230,217 @@ impl Tip {
     fn hash(&self) -> CryptoHash {
         self.last_block_hash
     }
+}
+
+/// A block-accepted event, versioned so the wire format can evolve independently of
+/// `ChainAdapter`'s in-process representation.
+#[derive(Debug, Clone)]
+pub enum VersionedBlockEvent {
+    V1(BlockEventV1),
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockEventV1 {
+    pub hash: CryptoHash,
+    pub height: BlockIndex,
+    pub status: BlockStatus,
+    pub total_weight: Weight,
+}
+
+/// Filter a subscriber registers when calling `EventStreamAdapter::subscribe`. Every non-empty
+/// criterion must match for an event to be delivered; a `None`/empty criterion matches anything.
+#[derive(Debug, Clone, Default)]
+pub struct EventSubscriptionRequest {
+    /// Only deliver events whose `BlockStatus` is in this list.
+    pub statuses: Vec<BlockStatus>,
+    /// Only deliver blocks whose height falls in this range.
+    pub height_range: Option<Range<BlockIndex>>,
+    /// Only deliver blocks signed by this authority.
+    pub authority: Option<AuthorityId>,
+}
+
+impl EventSubscriptionRequest {
+    fn matches(&self, status: BlockStatus, header: &BlockHeader) -> bool {
+        if !self.statuses.is_empty() && !self.statuses.contains(&status) {
+            return false;
+        }
+
+        if let Some(height_range) = &self.height_range {
+            if !height_range.contains(&header.height) {
+                return false;
+            }
+        }
+
+        if let Some(authority_id) = self.authority {
+            if !header.signatures.iter().any(|(signer, _)| *signer == authority_id) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// A subscriber's filter and the channel its matching events are sent over.
+struct Subscriber {
+    filter: EventSubscriptionRequest,
+    sender: mpsc::Sender<VersionedBlockEvent>,
+}
+
+/// A `ChainAdapter` that turns `block_accepted` into a subscribable `Stream` of versioned block
+/// events, for external consumers like explorers and indexers that would otherwise have to poll.
+///
+/// Slow consumers never stall block processing: sends are non-blocking, and a subscriber whose
+/// channel is full or disconnected is dropped rather than retried.
+pub struct EventStreamAdapter {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl EventStreamAdapter {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(vec![]),
+        }
+    }
+
+    /// Subscribes to block events matching `filter`, returning a `Stream` of
+    /// `VersionedBlockEvent`s. Intended to be hooked up through `spawn_network` so subscriptions
+    /// travel over the same transport the `protocol` module already uses.
+    pub fn subscribe(&self, filter: EventSubscriptionRequest) -> mpsc::Receiver<VersionedBlockEvent> {
+        let (sender, receiver) = mpsc::channel(1024);
+
+        self.subscribers.lock().unwrap().push(Subscriber { filter, sender });
+
+        receiver
+    }
+}
+
+impl ChainAdapter for EventStreamAdapter {
+    fn block_accepted(&self, block: &Block, status: BlockStatus) {
+        let event = VersionedBlockEvent::V1(BlockEventV1 {
+            hash: block.hash(),
+            height: block.header.height,
+            status,
+            total_weight: block.header.total_weight,
+        });
+
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut kept = Vec::with_capacity(subscribers.len());
+
+        // Send through the subscriber's own sender, not a fresh clone: every sender gets a
+        // guaranteed slot, so a clone's `try_send` would never report the channel as full.
+        for mut subscriber in subscribers.drain(..) {
+            if !subscriber.filter.matches(status, &block.header)
+                || subscriber.sender.try_send(event.clone()).is_ok()
+            {
+                kept.push(subscriber);
+            }
+        }
+
+        *subscribers = kept;
+    }
+}
+
+/// An aggregate proof that a supermajority of the authority set's weight finalized a specific
+/// block. Unlike `BlockHeader::signatures`, a `Justification` can be checked independently of
+/// the surrounding chain, giving a syncing node a finality checkpoint without replaying every
+/// header's signatures.
+#[derive(Debug, Clone)]
+pub struct Justification {
+    /// Hash of the block being finalized.
+    pub block_hash: CryptoHash,
+    /// Height of the block being finalized.
+    pub height: BlockIndex,
+    /// Each contributing authority's signature over `(block_hash, height)`, at most one per
+    /// authority.
+    pub signatures: Vec<(AuthorityId, Signature)>,
+}
+
+/// The message a `Justification`'s signatures are taken over.
+fn justification_message(block_hash: CryptoHash, height: BlockIndex) -> Vec<u8> {
+    let mut message = block_hash.as_ref().to_vec();
+    message.extend_from_slice(&height.to_le_bytes());
+    message
+}
+
+impl Justification {
+    /// Verifies this justification independently of the surrounding chain: that it targets
+    /// `expected_block_hash`/`expected_height`, that each listed authority actually signed
+    /// `(block_hash, height)` under the key given in `authority_keys` (indexed by `AuthorityId`),
+    /// and that the signing authorities collectively hold more than 2/3 of the weight in
+    /// `authority_weights` (also indexed by `AuthorityId`).
+    pub fn verify(
+        &self,
+        authority_weights: &[Weight],
+        authority_keys: &[PublicKey],
+        expected_block_hash: CryptoHash,
+        expected_height: BlockIndex,
+    ) -> bool {
+        if self.block_hash != expected_block_hash || self.height != expected_height {
+            return false;
+        }
+
+        // Reject rather than silently dedup a repeated signer: otherwise a single authority
+        // listed twice could inflate its own weight past the 2/3 threshold on its own.
+        let mut unique_signers = HashSet::new();
+        if !self.signatures.iter().all(|(authority_id, _)| unique_signers.insert(*authority_id)) {
+            return false;
+        }
+
+        let total_weight: u64 = authority_weights.iter().map(Weight::to_num).sum();
+        let signing_weight: u64 = unique_signers
+            .iter()
+            .filter_map(|&authority_id| authority_weights.get(authority_id))
+            .map(Weight::to_num)
+            .sum();
+
+        if 3 * signing_weight <= 2 * total_weight {
+            return false;
+        }
+
+        let message = justification_message(self.block_hash, self.height);
+
+        self.signatures.iter().all(|(authority_id, signature)| {
+            authority_keys
+                .get(*authority_id)
+                .map_or(false, |public_key| verify(&message, signature, public_key))
+        })
+    }
+}
+
+/// Controls how often the chain generates a `Justification`, to bound the overhead of proving
+/// finality compared to attaching one to every block.
+#[derive(Debug, Clone, Copy)]
+pub struct JustificationConfig {
+    /// A `Justification` is generated only for heights that are a multiple of `period`.
+    pub period: BlockIndex,
+}
+
+impl Default for JustificationConfig {
+    fn default() -> Self {
+        Self { period: 512 }
+    }
+}
+
+/// Whether the block at `height` is due a `Justification` under `config`.
+pub fn should_justify(config: &JustificationConfig, height: BlockIndex) -> bool {
+    config.period != 0 && height % config.period == 0
+}
+
+/// Accepts a block together with its `Justification` during sync: verifies the justification
+/// independently of the surrounding chain and, if valid, imports it by notifying `adapter`.
+/// Returns whether the justification verified.
+pub fn import_block_with_justification<A: ChainAdapter>(
+    adapter: &A,
+    block: &Block,
+    justification: &Justification,
+    authority_weights: &[Weight],
+    authority_keys: &[PublicKey],
+) -> bool {
+    if !justification.verify(authority_weights, authority_keys, block.hash(), block.header.height) {
+        return false;
+    }
+
+    adapter.justification_imported(block.hash(), block.header.height);
+    true
 }
\ No newline at end of file
@@ -0,0 +1,27 @@
+use futures::Stream;
+
+use crate::protocol::Package;
+use crate::proxy::ProxyHandler;
+
+/// Wires a sequence of `ProxyHandler`s together so a `Package` flows through each of them, in
+/// order, over a single stream. Lets a test stack e.g. throttling + dropout + partition to
+/// reproduce adversarial network conditions with a single handler.
+pub struct ChainedHandler {
+    handlers: Vec<Box<ProxyHandler>>,
+}
+
+impl ChainedHandler {
+    pub fn new(handlers: Vec<Box<ProxyHandler>>) -> Self {
+        Self {
+            handlers
+        }
+    }
+}
+
+impl ProxyHandler for ChainedHandler {
+    fn pipe_stream(&self, stream: Box<Stream<Item=Package, Error=()> + Send + Sync>) ->
+    Box<Stream<Item=Package, Error=()> + Send + Sync>
+    {
+        self.handlers.iter().fold(stream, |stream, handler| handler.pipe_stream(stream))
+    }
+}
@@ -0,0 +1,50 @@
+use futures::future::Future;
+use futures::sink::Sink;
+use futures::Stream;
+use futures::sync::mpsc::channel;
+use rand::Rng;
+
+use crate::protocol::Package;
+use crate::proxy::ProxyHandler;
+
+/// Messages passing through this handler will be dropped independently with probability
+/// `dropout_rate`.
+pub struct DropoutHandler {
+    dropout_rate: f64,
+}
+
+impl DropoutHandler {
+    pub fn new(dropout_rate: f64) -> Self {
+        Self {
+            dropout_rate
+        }
+    }
+}
+
+impl ProxyHandler for DropoutHandler {
+    fn pipe_stream(&self, stream: Box<Stream<Item=Package, Error=()> + Send + Sync>) ->
+    Box<Stream<Item=Package, Error=()> + Send + Sync>
+    {
+        let (message_tx, message_rx) = channel(1024);
+        let dropout_rate = self.dropout_rate;
+
+        let main_task = stream.for_each(move |package| {
+            let mut rng = rand::thread_rng();
+
+            if rng.gen::<f64>() >= dropout_rate {
+                let send_task = message_tx
+                    .clone()
+                    .send(package)
+                    .map(|_| ())
+                    .map_err(|_| ());
+
+                tokio::spawn(send_task);
+            }
+
+            Ok(())
+        });
+
+        tokio::spawn(main_task);
+        Box::new(message_rx)
+    }
+}
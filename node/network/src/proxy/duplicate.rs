@@ -0,0 +1,57 @@
+use futures::future::Future;
+use futures::sink::Sink;
+use futures::Stream;
+use futures::sync::mpsc::channel;
+use rand::Rng;
+
+use crate::protocol::Package;
+use crate::proxy::ProxyHandler;
+
+/// Messages passing through this handler are re-emitted a second time with probability
+/// `duplicate_rate`, simulating a link that occasionally delivers the same message twice.
+pub struct DuplicateHandler {
+    duplicate_rate: f64,
+}
+
+impl DuplicateHandler {
+    pub fn new(duplicate_rate: f64) -> Self {
+        Self {
+            duplicate_rate
+        }
+    }
+}
+
+impl ProxyHandler for DuplicateHandler {
+    fn pipe_stream(&self, stream: Box<Stream<Item=Package, Error=()> + Send + Sync>) ->
+    Box<Stream<Item=Package, Error=()> + Send + Sync>
+    {
+        let (message_tx, message_rx) = channel(1024);
+        let duplicate_rate = self.duplicate_rate;
+
+        let main_task = stream.for_each(move |package| {
+            let mut rng = rand::thread_rng();
+            let duplicate = rng.gen::<f64>() < duplicate_rate;
+
+            let message_tx1 = message_tx.clone();
+            let send_task = message_tx1
+                .send(package.clone())
+                .map(|_| ())
+                .map_err(|_| ());
+            tokio::spawn(send_task);
+
+            if duplicate {
+                let message_tx2 = message_tx.clone();
+                let resend_task = message_tx2
+                    .send(package)
+                    .map(|_| ())
+                    .map_err(|_| ());
+                tokio::spawn(resend_task);
+            }
+
+            Ok(())
+        });
+
+        tokio::spawn(main_task);
+        Box::new(message_rx)
+    }
+}
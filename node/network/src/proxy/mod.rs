@@ -0,0 +1,25 @@
+use futures::Stream;
+
+use crate::protocol::Package;
+
+mod chained;
+mod dropout;
+mod duplicate;
+mod partition;
+mod reorder;
+mod throttling;
+
+pub use crate::proxy::chained::ChainedHandler;
+pub use crate::proxy::dropout::DropoutHandler;
+pub use crate::proxy::duplicate::DuplicateHandler;
+pub use crate::proxy::partition::PartitionHandler;
+pub use crate::proxy::reorder::ReorderHandler;
+pub use crate::proxy::throttling::ThrottlingHandler;
+
+/// A `ProxyHandler` sits in the middle of a stream of `Package`s flowing between two peers and
+/// can delay, drop, duplicate or reorder them, to reproduce adversarial network conditions in
+/// `protocol`/`peer_manager` tests.
+pub trait ProxyHandler: Send + Sync {
+    fn pipe_stream(&self, stream: Box<Stream<Item=Package, Error=()> + Send + Sync>) ->
+    Box<Stream<Item=Package, Error=()> + Send + Sync>;
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::future::Future;
+use futures::sink::Sink;
+use futures::Stream;
+use futures::sync::mpsc::channel;
+
+use crate::protocol::Package;
+use crate::proxy::ProxyHandler;
+
+/// Blocks packages exchanged between two disjoint groups of peers for a fixed time window,
+/// simulating a network partition. `peers_of` extracts the `(sender, receiver)` peer ids from a
+/// `Package`, keeping this handler agnostic of the concrete peer id representation.
+pub struct PartitionHandler {
+    groups: Vec<Vec<String>>,
+    until: Instant,
+    peers_of: Arc<Fn(&Package) -> (String, String) + Send + Sync>,
+}
+
+impl PartitionHandler {
+    pub fn new<F>(groups: Vec<Vec<String>>, window: Duration, peers_of: F) -> Self
+    where
+        F: Fn(&Package) -> (String, String) + Send + Sync + 'static,
+    {
+        Self {
+            groups,
+            until: Instant::now() + window,
+            peers_of: Arc::new(peers_of),
+        }
+    }
+
+    fn blocks(groups: &[Vec<String>], sender: &str, receiver: &str) -> bool {
+        groups.iter().any(|group| {
+            let sender_in_group = group.iter().any(|peer| peer == sender);
+            let receiver_in_group = group.iter().any(|peer| peer == receiver);
+            sender_in_group != receiver_in_group
+        })
+    }
+}
+
+impl ProxyHandler for PartitionHandler {
+    fn pipe_stream(&self, stream: Box<Stream<Item=Package, Error=()> + Send + Sync>) ->
+    Box<Stream<Item=Package, Error=()> + Send + Sync>
+    {
+        let (message_tx, message_rx) = channel(1024);
+        let groups = self.groups.clone();
+        let until = self.until;
+        let peers_of = self.peers_of.clone();
+
+        let main_task = stream.for_each(move |package| {
+            let (sender, receiver) = peers_of(&package);
+            let partitioned = Instant::now() < until && Self::blocks(&groups, &sender, &receiver);
+
+            if !partitioned {
+                let send_task = message_tx
+                    .clone()
+                    .send(package)
+                    .map(|_| ())
+                    .map_err(|_| ());
+
+                tokio::spawn(send_task);
+            }
+
+            Ok(())
+        });
+
+        tokio::spawn(main_task);
+        Box::new(message_rx)
+    }
+}
@@ -0,0 +1,51 @@
+use futures::future::Future;
+use futures::sink::Sink;
+use futures::Stream;
+use futures::sync::mpsc::channel;
+use rand::seq::SliceRandom;
+
+use crate::protocol::Package;
+use crate::proxy::ProxyHandler;
+
+/// Buffers `buffer_size` packages and flushes them back out in shuffled order, simulating a
+/// link that reorders messages in transit.
+pub struct ReorderHandler {
+    buffer_size: usize,
+}
+
+impl ReorderHandler {
+    pub fn new(buffer_size: usize) -> Self {
+        Self {
+            buffer_size
+        }
+    }
+}
+
+impl ProxyHandler for ReorderHandler {
+    fn pipe_stream(&self, stream: Box<Stream<Item=Package, Error=()> + Send + Sync>) ->
+    Box<Stream<Item=Package, Error=()> + Send + Sync>
+    {
+        let (message_tx, message_rx) = channel(1024);
+        let buffer_size = self.buffer_size;
+
+        let main_task = stream.chunks(buffer_size).for_each(move |mut packages| {
+            let mut rng = rand::thread_rng();
+            packages.shuffle(&mut rng);
+
+            for package in packages {
+                let send_task = message_tx
+                    .clone()
+                    .send(package)
+                    .map(|_| ())
+                    .map_err(|_| ());
+
+                tokio::spawn(send_task);
+            }
+
+            Ok(())
+        });
+
+        tokio::spawn(main_task);
+        Box::new(message_rx)
+    }
+}